@@ -5,12 +5,29 @@ use kern::{version, Fail};
 use std::collections::BTreeMap;
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
+use std::time::{Duration, Instant};
 
-/// HTTP request method (GET or POST)
+/// Byte range requested via the `Range` header
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// `bytes=start-` (from start to end of content)
+    From(usize),
+    /// `bytes=start-end` (inclusive)
+    Full(usize, usize),
+    /// `bytes=-len` (last len bytes of content)
+    Suffix(usize),
+}
+
+/// HTTP request method
 #[derive(Debug, PartialEq)]
 pub enum HttpMethod {
     GET,
     POST,
+    PUT,
+    DELETE,
+    HEAD,
+    OPTIONS,
+    PATCH,
 }
 
 /// HTTP request structure
@@ -20,7 +37,7 @@ pub struct HttpRequest<'a> {
     url: &'a str,
     headers: BTreeMap<&'a str, &'a str>,
     get: BTreeMap<&'a str, &'a str>,
-    body: String,
+    body: Vec<u8>,
 }
 
 // HTTP request implementation
@@ -36,10 +53,15 @@ impl<'a> HttpRequest<'a> {
         let mut reqln = header.next()?.split(' ');
 
         // parse method
-        let method = if reqln.next()? == "POST" {
-            HttpMethod::POST
-        } else {
-            HttpMethod::GET
+        let method = match reqln.next()? {
+            "GET" => HttpMethod::GET,
+            "POST" => HttpMethod::POST,
+            "PUT" => HttpMethod::PUT,
+            "DELETE" => HttpMethod::DELETE,
+            "HEAD" => HttpMethod::HEAD,
+            "OPTIONS" => HttpMethod::OPTIONS,
+            "PATCH" => HttpMethod::PATCH,
+            _ => return None,
         };
 
         // parse url and split raw get parameters
@@ -76,20 +98,27 @@ impl<'a> HttpRequest<'a> {
             headers.get("content-length")
         };
 
+        // check for chunked transfer encoding
+        let chunked = headers
+            .get("Transfer-Encoding")
+            .or_else(|| headers.get("transfer-encoding"))
+            .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+
+        // reject requests carrying both headers: letting Content-Length win
+        // here would let a front-end and this server disagree on where the
+        // body ends, which is the classic request-smuggling vector
+        if buf_len.is_some() && chunked {
+            return None;
+        }
+
         // check max log size and read body
-        let mut body = String::new();
+        let mut body = Vec::new();
         if let Some(buf_len) = buf_len {
             // parse buffer length
             let con_len = buf_len.parse::<usize>().ok()?;
             if con_len > max_content {
                 // max log size exceeded
-                respond(stream, format!(
-                    "{}{}<div class=\"alert alert-danger\" role=\"alert\">Maximale Log-Größe überschritten</div>{}",
-                    HEAD, BACK, footer()
-                )
-                .as_bytes(),
-                "text/html",
-                None).unwrap();
+                respond_oversize(stream).ok()?;
                 return None;
             } else {
                 // read body
@@ -101,7 +130,17 @@ impl<'a> HttpRequest<'a> {
                     raw_body.append(&mut rest_body);
                     tries += 1;
                 }
-                body = String::from_utf8(raw_body).ok()?;
+                body = raw_body;
+            }
+        } else if chunked {
+            // decode chunked body, reusing the already-buffered bytes
+            match decode_chunked(stream, raw_body, max_content)? {
+                ChunkedBody::TooLarge => {
+                    // max log size exceeded
+                    respond_oversize(stream).ok()?;
+                    return None;
+                }
+                ChunkedBody::Ok(decoded) => body = decoded,
             }
         }
 
@@ -116,15 +155,27 @@ impl<'a> HttpRequest<'a> {
         })
     }
 
-    /// Parse POST parameters
-    pub fn post(&self) -> Option<BTreeMap<&str, &str>> {
+    /// Parse multipart/form-data POST fields
+    pub fn post(
+        &self,
+        max_file_size: usize,
+        max_num_files: usize,
+    ) -> Option<Vec<MultipartField<'_>>> {
         // check if POST method used
         if self.method == HttpMethod::POST {
-            // parse POST parameters
-            parse_upload(&self.body)
+            // get boundary token from Content-Type header
+            let content_type = if let Some(content_type) = self.headers.get("Content-Type") {
+                content_type
+            } else {
+                self.headers.get("content-type")?
+            };
+            let boundary = parse_boundary(content_type)?;
+
+            // parse multipart fields
+            parse_upload(&self.body, boundary, max_file_size, max_num_files)
         } else {
-            // no POST request: return empty map
-            Some(BTreeMap::new())
+            // no POST request: return empty list
+            Some(Vec::new())
         }
     }
 
@@ -153,50 +204,198 @@ impl<'a> HttpRequest<'a> {
         // return GET parameters map
         &self.get
     }
+
+    /// Get cookies from the Cookie header
+    pub fn cookies(&self) -> BTreeMap<&str, &str> {
+        // get Cookie header
+        let raw = if let Some(raw) = self.headers.get("Cookie") {
+            raw
+        } else if let Some(raw) = self.headers.get("cookie") {
+            raw
+        } else {
+            return BTreeMap::new();
+        };
+
+        // split pairs by "; " and key/value by "="
+        let mut cookies = BTreeMap::new();
+        for pair in raw.split("; ") {
+            let mut kv = pair.splitn(2, '=');
+            if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+                cookies.insert(key, value);
+            }
+        }
+
+        // return cookies map
+        cookies
+    }
+
+    /// Get the raw, binary-safe request body
+    pub fn body_bytes(&self) -> &[u8] {
+        // return body bytes
+        &self.body
+    }
+
+    /// Get the request body as UTF-8, if it is valid
+    pub fn body_str(&self) -> Option<&str> {
+        // try to decode body as UTF-8
+        std::str::from_utf8(&self.body).ok()
+    }
+
+    /// Get requested byte range from the Range header, if present
+    pub fn range(&self) -> Option<ByteRange> {
+        // get Range header
+        let raw = if let Some(raw) = self.headers.get("Range") {
+            raw
+        } else {
+            self.headers.get("range")?
+        };
+
+        // parse it
+        parse_range(raw)
+    }
 }
 
-// Parse POST file upload with parameters to map
-fn parse_upload(body: &str) -> Option<BTreeMap<&str, &str>> {
-    // parameters map
-    let mut params = BTreeMap::new();
+/// Parsed multipart/form-data field
+#[derive(Debug)]
+pub struct MultipartField<'a> {
+    /// Field name (`name` in `Content-Disposition`)
+    pub name: &'a str,
+    /// Original filename, if the field came from a file input
+    pub filename: Option<&'a str>,
+    /// Declared `Content-Type` of the part, if any
+    pub content_type: Option<&'a str>,
+    /// Raw, binary-safe field value
+    pub value: &'a [u8],
+}
 
-    // split file upload body into sections
-    for content in body.split("\r\n---") {
-        // split lines (max 4)
-        let mut lines = content.splitn(4, "\r\n").skip(1);
-        let mut name = "";
-
-        // split in phrases
-        for line in lines.next()?.split(';').map(|line| line.trim()) {
-            // check if phrase contains name
-            if line.starts_with("name=") {
-                if line.len() > 6 {
-                    // get name
-                    name = &line[6..(line.len() - 1)];
-                    break;
-                } else {
-                    // no name
-                    return None;
+// Extract the boundary token from a Content-Type header value
+fn parse_boundary(content_type: &str) -> Option<&str> {
+    // find the boundary parameter
+    let boundary = content_type
+        .split(';')
+        .map(|part| part.trim())
+        .find_map(|part| part.strip_prefix("boundary="))?;
+
+    // strip an optional surrounding quoted-string pair
+    Some(
+        boundary
+            .strip_prefix('"')
+            .and_then(|boundary| boundary.strip_suffix('"'))
+            .unwrap_or(boundary),
+    )
+}
+
+// Parse a multipart/form-data body into fields, bounded by max_file_size/max_num_files
+fn parse_upload<'a>(
+    body: &'a [u8],
+    boundary: &str,
+    max_file_size: usize,
+    max_num_files: usize,
+) -> Option<Vec<MultipartField<'a>>> {
+    // fields list
+    let mut fields = Vec::new();
+    let mut num_files = 0;
+
+    // split body into parts delimited by --boundary
+    let delimiter = format!("--{}", boundary);
+    let mut parts = split_on(body, delimiter.as_bytes()).into_iter();
+    parts.next()?; // discard preamble before the first boundary
+
+    for part in parts {
+        // closing boundary is "--boundary--"
+        if part.starts_with(b"--") {
+            break;
+        }
+
+        // split part headers from its value on the first blank line
+        let part = part.strip_prefix(b"\r\n")?;
+        let header_end = find_subslice(part, b"\r\n\r\n")?;
+        let (header_block, value) = part.split_at(header_end);
+        let value = &value[4..];
+        let value = value.strip_suffix(b"\r\n").unwrap_or(value);
+
+        // parse Content-Disposition and optional Content-Type
+        let mut name = None;
+        let mut filename = None;
+        let mut content_type = None;
+        for line in std::str::from_utf8(header_block).ok()?.split("\r\n") {
+            let mut hls = line.splitn(2, ':');
+            let (key, value) = (hls.next()?, hls.next()?.trim());
+            if key.eq_ignore_ascii_case("Content-Disposition") {
+                for phrase in value.split(';').map(|phrase| phrase.trim()) {
+                    if let Some(n) = phrase
+                        .strip_prefix("name=\"")
+                        .and_then(|n| n.strip_suffix('"'))
+                    {
+                        name = Some(n);
+                    } else if let Some(f) = phrase
+                        .strip_prefix("filename=\"")
+                        .and_then(|f| f.strip_suffix('"'))
+                    {
+                        filename = Some(f);
+                    }
                 }
+            } else if key.eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value);
             }
         }
 
-        // get next line
-        if let Some(value) = lines.next() {
-            // check for empty line
-            if value == "" {
-                // add next line to parameters map
-                params.insert(name, lines.next()?);
-            } else {
-                // ignore first empty line and add second line to parameters map
-                let mut a = lines.next()?.splitn(2, "\r\n");
-                params.insert(name, a.nth(1)?);
+        // enforce per-field size and file count limits
+        if value.len() > max_file_size {
+            return None;
+        }
+        if filename.is_some() {
+            num_files += 1;
+            if num_files > max_num_files {
+                return None;
             }
         }
+
+        fields.push(MultipartField {
+            name: name?,
+            filename,
+            content_type,
+            value,
+        });
     }
 
-    // return parameters map
-    Some(params)
+    // return parsed fields
+    Some(fields)
+}
+
+// Split a byte slice on every occurrence of a delimiter
+fn split_on<'a>(data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    let mut rest = data;
+    while let Some(pos) = find_subslice(rest, delimiter) {
+        parts.push(&rest[..pos]);
+        rest = &rest[pos + delimiter.len()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+// Find the first occurrence of needle in data
+fn find_subslice(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+// Parse a Range header value into a ByteRange
+fn parse_range(raw: &str) -> Option<ByteRange> {
+    // strip unit prefix
+    let spec = raw.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+
+    if start.is_empty() {
+        // bytes=-<len>: last len bytes
+        Some(ByteRange::Suffix(end.parse().ok()?))
+    } else if end.is_empty() {
+        // bytes=<start>-: from start to the end
+        Some(ByteRange::From(start.parse().ok()?))
+    } else {
+        // bytes=<start>-<end>: inclusive range
+        Some(ByteRange::Full(start.parse().ok()?, end.parse().ok()?))
+    }
 }
 
 // Parse GET parameters to map
@@ -222,95 +421,751 @@ fn parse_parameters(raw: &str) -> Option<BTreeMap<&str, &str>> {
     Some(params)
 }
 
+/// HTTP response builder
+pub struct Response {
+    status: u16,
+    reason: &'static str,
+    headers: BTreeMap<&'static str, String>,
+    cookies: Vec<String>,
+    body: Vec<u8>,
+    head_only: bool,
+    exact_length: bool,
+}
+
+// HTTP response builder implementation
+impl Response {
+    /// Create a new response with a status code and reason phrase
+    pub fn new(status: u16, reason: &'static str) -> Self {
+        Self {
+            status,
+            reason,
+            headers: BTreeMap::new(),
+            cookies: Vec::new(),
+            body: Vec::new(),
+            head_only: false,
+            exact_length: false,
+        }
+    }
+
+    /// Suppress writing the body (for HEAD requests), keeping Content-Length accurate
+    pub fn head_only(mut self, head_only: bool) -> Self {
+        self.head_only = head_only;
+        self
+    }
+
+    /// Use the true body length as Content-Length instead of the trailing-`\r\n` padded one,
+    /// and skip writing that trailing `\r\n` (needed for byte-exact responses like 206/416)
+    pub fn exact_length(mut self) -> Self {
+        self.exact_length = true;
+        self
+    }
+
+    /// Set a header, overriding any previous value for the same key
+    pub fn header(mut self, key: &'static str, value: String) -> Self {
+        self.headers.insert(key, value);
+        self
+    }
+
+    /// Append a Set-Cookie header
+    pub fn cookie(mut self, cookie: String) -> Self {
+        self.cookies.push(cookie);
+        self
+    }
+
+    /// Set Content-Disposition for a file download
+    pub fn filename(self, filename: &str) -> Self {
+        self.header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        )
+    }
+
+    /// Set the response body
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Write the response to the stream
+    pub fn write_to(mut self, stream: &mut TcpStream) -> io::Result<()> {
+        // default headers
+        self.headers
+            .entry("Server")
+            .or_insert_with(|| format!("ltheinrich.de/stratos v{}", version()));
+        let exact_length = self.exact_length;
+        let body_len = self.body.len();
+        self.headers.entry("Content-Length").or_insert_with(|| {
+            if exact_length {
+                body_len.to_string()
+            } else {
+                (body_len + 2).to_string() // bugfix (proxying)
+            }
+        });
+
+        // status line and headers
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        for (key, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        for cookie in &self.cookies {
+            head.push_str(&format!("Set-Cookie: {}\r\n", cookie));
+        }
+        head.push_str("\r\n");
+
+        // assemble headers and body into a single write so they arrive in one segment
+        let mut out = head.into_bytes();
+        if !self.head_only {
+            out.extend_from_slice(&self.body);
+            if !self.exact_length {
+                out.extend_from_slice(b"\r\n");
+            }
+        }
+        stream.write_all(&out)?;
+        stream.flush()
+    }
+}
+
+// Respond with the shared "max log size exceeded" error page
+fn respond_oversize(stream: &mut TcpStream) -> io::Result<()> {
+    Response::new(413, "Payload Too Large")
+        .header("Content-Type", "text/html".to_string())
+        .body(format!(
+            "{}{}<div class=\"alert alert-danger\" role=\"alert\">Maximale Log-Größe überschritten</div>{}",
+            HEAD, BACK, footer()
+        ))
+        .write_to(stream)
+}
+
 /// HTTP responder
 pub fn respond(
     stream: &mut TcpStream,
+    method: &HttpMethod,
     content: &[u8],
     content_type: &str,
     filename: Option<&str>,
 ) -> io::Result<()> {
-    // write headers to stream
-    stream
-        .write_all(format!(
-            "HTTP/1.1 200 OK\r\nServer: ltheinrich.de/stratos v{}\r\nContent-Type: {}\r\nContent-Length: {}{}\r\n\r\n",
-            version(),
-            content_type,
-            content.len() + 2, // bugfix (proxying)
-            // optional filename for download
-            if let Some(filename) = filename {
-                format!("\r\nContent-Disposition: attachment; filename=\"{}\"", filename)
-            } else {
-                String::new()
-            }
-        )
-        .as_bytes())?;
+    // build and write 200 OK response
+    let mut response = Response::new(200, "OK")
+        .header("Content-Type", content_type.to_string())
+        .header("Accept-Ranges", "bytes".to_string())
+        .head_only(*method == HttpMethod::HEAD)
+        .body(content.to_vec());
+    if let Some(filename) = filename {
+        response = response.filename(filename);
+    }
+    response.write_to(stream)
+}
+
+/// HTTP responder with Range request support
+pub fn respond_range(
+    stream: &mut TcpStream,
+    method: &HttpMethod,
+    content: &[u8],
+    content_type: &str,
+    filename: Option<&str>,
+    range: Option<ByteRange>,
+) -> io::Result<()> {
+    // no range requested: fall back to the plain responder
+    let range = match range {
+        Some(range) => range,
+        None => return respond(stream, method, content, content_type, filename),
+    };
+
+    // resolve the range against the actual content length
+    let total = content.len();
+    let resolved = match range {
+        ByteRange::From(start) if start < total => Some((start, total - 1)),
+        ByteRange::Full(start, end) if start <= end && start < total => {
+            Some((start, end.min(total - 1)))
+        }
+        ByteRange::Suffix(len) if len > 0 && len <= total => Some((total - len, total - 1)),
+        // a suffix length longer than the resource clamps to the whole representation
+        ByteRange::Suffix(len) if len > total && total > 0 => Some((0, total - 1)),
+        _ => None,
+    };
+
+    // range not satisfiable against the content
+    let (start, end) = match resolved {
+        Some(range) => range,
+        None => {
+            return Response::new(416, "Range Not Satisfiable")
+                .header("Content-Range", format!("bytes */{}", total))
+                .exact_length()
+                .write_to(stream);
+        }
+    };
 
-    // write body and end
-    stream.write_all(content)?;
-    stream.write_all(b"\r\n")?;
-    stream.flush()
+    // build and write 206 Partial Content response
+    let slice = &content[start..=end];
+    let mut response = Response::new(206, "Partial Content")
+        .header("Content-Type", content_type.to_string())
+        .header("Accept-Ranges", "bytes".to_string())
+        .header(
+            "Content-Range",
+            format!("bytes {}-{}/{}", start, end, total),
+        )
+        .head_only(*method == HttpMethod::HEAD)
+        .exact_length()
+        .body(slice.to_vec());
+    if let Some(filename) = filename {
+        response = response.filename(filename);
+    }
+    response.write_to(stream)
 }
 
 /// HTTP redirecter
 pub fn redirect(stream: &mut TcpStream, url: &str) -> io::Result<()> {
-    // write redirect headers and simple body
-    stream.write_all(format!(
-        "HTTP/1.1 303 See Other\r\nServer: ltheinrich.de/stratos v{}\r\nLocation: {1}\r\n\r\n<html><head><title>Moved</title></head><body><h1>Moved</h1><p><a href=\"{1}\">{1}</a></p></body></html>\r\n",
-        version(),
-        url
-    )
-    .as_bytes())
+    // build and write 303 See Other response
+    Response::new(303, "See Other")
+        .header("Location", url.to_string())
+        .body(format!(
+            "<html><head><title>Moved</title></head><body><h1>Moved</h1><p><a href=\"{0}\">{0}</a></p></body></html>",
+            url
+        ))
+        .write_to(stream)
 }
 
-/// Read until \r\n\r\n (just working, uncommented)
-pub fn read_header(stream: &mut TcpStream) -> Result<(String, Vec<u8>), Fail> {
+// Outcome of decoding a chunked body
+enum ChunkedBody {
+    Ok(Vec<u8>),
+    TooLarge,
+}
+
+// a chunk-size line is just a hex length plus optional extensions, never this long
+const MAX_CHUNK_SIZE_LINE: usize = 4096;
+
+// Decode a Transfer-Encoding: chunked body, reusing already-buffered bytes
+fn decode_chunked(
+    stream: &mut TcpStream,
+    mut buf: Vec<u8>,
+    max_content: usize,
+) -> Option<ChunkedBody> {
+    let mut decoded = Vec::new();
+
+    // overall wall-clock budget across the whole decode, scaled the same way as
+    // the Content-Length path's `tries` cap (2s read timeout per "try"): without
+    // it a client trickling a byte at a time, each arriving just under the read
+    // timeout, could hold the thread open indefinitely without ever hitting
+    // MAX_CHUNK_SIZE_LINE or max_content
+    let start = Instant::now();
+    let budget = Duration::from_millis(2000) * (max_content / 1_048_576).max(5) as u32;
+
+    loop {
+        // make sure a full chunk-size line is buffered, bounded so a client that
+        // never sends \r\n can't grow buf without limit
+        while find_subslice(&buf, b"\r\n").is_none() {
+            if buf.len() > MAX_CHUNK_SIZE_LINE || start.elapsed() > budget {
+                return None;
+            }
+            let mut chunk = vec![0u8; 8192];
+            let length = stream.read(&mut chunk).ok()?;
+            if length == 0 {
+                return None;
+            }
+            chunk.truncate(length);
+            buf.append(&mut chunk);
+        }
+
+        // parse the chunk size (ignoring chunk extensions after ';')
+        let line_end = find_subslice(&buf, b"\r\n")?;
+        let size_line = std::str::from_utf8(&buf[..line_end]).ok()?;
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        buf.drain(..line_end + 2);
+
+        // terminating chunk: drain the trailer section (if any) and the
+        // final CRLF so trailing bytes don't desync the next request read
+        // off this stream
+        if size == 0 {
+            loop {
+                while find_subslice(&buf, b"\r\n").is_none() {
+                    if buf.len() > MAX_CHUNK_SIZE_LINE || start.elapsed() > budget {
+                        return None;
+                    }
+                    let mut chunk = vec![0u8; 8192];
+                    let length = stream.read(&mut chunk).ok()?;
+                    if length == 0 {
+                        return None;
+                    }
+                    chunk.truncate(length);
+                    buf.append(&mut chunk);
+                }
+
+                let line_end = find_subslice(&buf, b"\r\n")?;
+                buf.drain(..line_end + 2);
+
+                // an empty line ends the trailer section
+                if line_end == 0 {
+                    break;
+                }
+            }
+            return Some(ChunkedBody::Ok(decoded));
+        }
+
+        // enforce max content size (checked to avoid overflow on a bogus huge chunk size)
+        if size > max_content.saturating_sub(decoded.len()) {
+            return Some(ChunkedBody::TooLarge);
+        }
+
+        // make sure the full chunk data plus its trailing CRLF is buffered
+        while buf.len() < size + 2 {
+            if start.elapsed() > budget {
+                return None;
+            }
+            let mut chunk = vec![0u8; 8192];
+            let length = stream.read(&mut chunk).ok()?;
+            if length == 0 {
+                return None;
+            }
+            chunk.truncate(length);
+            buf.append(&mut chunk);
+        }
+
+        // append chunk data and drop it plus its trailing CRLF
+        decoded.extend_from_slice(&buf[..size]);
+        buf.drain(..size + 2);
+    }
+}
+
+/// Read until \r\n\r\n, bounded by max_header, returning the header and any bytes read past it
+pub fn read_header(stream: &mut TcpStream, max_header: usize) -> Result<(String, Vec<u8>), Fail> {
     let mut header = Vec::new();
-    let mut rest = Vec::new();
     let mut buf = vec![0u8; 8192];
 
-    'l: while buf.len() < 16384 {
+    loop {
+        // read the next chunk
         let length = match stream.read(&mut buf) {
+            Ok(0) => return Fail::from("connection closed before headers were complete"),
             Ok(length) => length,
             Err(err) => return Fail::from(err),
         };
-        for (i, &c) in buf.iter().enumerate() {
-            if c == b'\r' {
-                if buf.len() < i + 4 {
-                    let mut buf_temp = vec![0u8; buf.len() - (i + 4)];
-                    match stream.read(&mut buf_temp) {
-                        Ok(_) => {}
-                        Err(err) => return Fail::from(err),
-                    };
-                    let buf2 = [&buf[..], &buf_temp[..]].concat();
-                    if buf2[i + 1] == b'\n' && buf2[i + 2] == b'\r' && buf2[i + 3] == b'\n' {
-                        header.append(&mut buf);
-                        header.append(&mut buf_temp);
-                        break 'l;
-                    }
-                } else if buf[i + 1] == b'\n' && buf[i + 2] == b'\r' && buf[i + 3] == b'\n' {
-                    for &b in buf.iter().take(i + 4) {
-                        header.push(b);
-                    }
-                    for &b in buf.iter().take(length).skip(i + 4) {
-                        rest.push(b);
-                    }
-                    break 'l;
-                } else if i + 1 == buf.len() {
-                    for &b in buf.iter().take(i + 4) {
-                        header.push(b);
-                    }
-                    for &b in buf.iter().take(length).skip(i + 4) {
-                        rest.push(b);
-                    }
-                }
+
+        // only rescan the last 3 already-seen bytes, so a terminator split
+        // across two reads is still found without rescanning from the start
+        let search_from = header.len().saturating_sub(3);
+        header.extend_from_slice(&buf[..length]);
+
+        if let Some(pos) = find_subslice(&header[search_from..], b"\r\n\r\n") {
+            // split off the body bytes that were read along with the header
+            let rest = header.split_off(search_from + pos + 4);
+            return Ok((
+                match String::from_utf8(header) {
+                    Ok(header) => header,
+                    Err(err) => return Fail::from(err),
+                },
+                rest,
+            ));
+        }
+
+        // enforce maximum header size instead of looping/panicking forever,
+        // but only once we know the terminator wasn't in this read
+        if header.len() > max_header {
+            return Fail::from("header exceeds maximum size");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    // connect a loopback TcpStream pair for exercising read_header
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || TcpStream::connect(addr).unwrap());
+        let (server, _) = listener.accept().unwrap();
+        (server, client.join().unwrap())
+    }
+
+    // read until the peer closes the connection, since header and body may
+    // arrive as separate TCP segments rather than in a single read()
+    fn read_all(stream: &mut TcpStream) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => out.extend_from_slice(&buf[..n]),
+                Err(e) => panic!("read failed: {}", e),
             }
         }
+        out
+    }
+
+    #[test]
+    fn response_writes_one_set_cookie_header_per_cookie() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            Response::new(200, "OK")
+                .cookie("a=1".to_string())
+                .cookie("b=2".to_string())
+                .body("ok")
+                .write_to(&mut server)
+                .unwrap();
+        });
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let response = String::from_utf8_lossy(&buf[..n]);
+        assert_eq!(response.matches("Set-Cookie: a=1").count(), 1, "{}", response);
+        assert_eq!(response.matches("Set-Cookie: b=2").count(), 1, "{}", response);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn header_split_across_multiple_reads() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            client.write_all(b"Host: example\r\n\r\nbody").unwrap();
+        });
+
+        let (header, rest) = read_header(&mut server, 16384).unwrap();
+        assert_eq!(header, "GET / HTTP/1.1\r\nHost: example\r\n\r\n");
+        assert_eq!(rest, b"body");
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn terminator_on_buffer_edge() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            // pad the first read to exactly fill read_header's 8192-byte buffer,
+            // splitting the \r\n\r\n terminator across the read boundary
+            let mut first = vec![b'a'; 8190];
+            first.extend_from_slice(b"\r\n");
+            client.write_all(&first).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            client.write_all(b"\r\nbody").unwrap();
+        });
+
+        let (header, rest) = read_header(&mut server, 16384).unwrap();
+        assert_eq!(header.len(), 8194);
+        assert!(header.ends_with("\r\n\r\n"));
+        assert_eq!(rest, b"body");
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn header_exceeding_max_size_fails() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            client.write_all(&vec![b'a'; 9000]).unwrap();
+        });
+
+        assert!(read_header(&mut server, 8192).is_err());
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn boundary_strips_surrounding_quotes() {
+        assert_eq!(
+            parse_boundary("multipart/form-data; boundary=\"BOUNDARY\""),
+            Some("BOUNDARY")
+        );
+        assert_eq!(
+            parse_boundary("multipart/form-data; boundary=BOUNDARY"),
+            Some("BOUNDARY")
+        );
+    }
+
+    #[test]
+    fn multipart_parses_two_fields_including_a_file() {
+        let body = b"--BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"field1\"\r\n\
+            \r\n\
+            value1\r\n\
+            --BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"file1\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            file contents\r\n\
+            --BOUNDARY--\r\n";
+
+        let fields = parse_upload(body, "BOUNDARY", 1_048_576, 10).unwrap();
+        assert_eq!(fields.len(), 2);
+
+        assert_eq!(fields[0].name, "field1");
+        assert_eq!(fields[0].filename, None);
+        assert_eq!(fields[0].value, b"value1");
+
+        assert_eq!(fields[1].name, "file1");
+        assert_eq!(fields[1].filename, Some("a.txt"));
+        assert_eq!(fields[1].content_type, Some("text/plain"));
+        assert_eq!(fields[1].value, b"file contents");
+    }
+
+    #[test]
+    fn multipart_field_over_max_file_size_is_none() {
+        let body = b"--BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"field1\"\r\n\
+            \r\n\
+            value1\r\n\
+            --BOUNDARY--\r\n";
+
+        // "value1" is 6 bytes, one byte over the limit
+        assert!(parse_upload(body, "BOUNDARY", 5, 10).is_none());
+    }
+
+    #[test]
+    fn multipart_too_many_files_is_none() {
+        let body = b"--BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"file1\"; filename=\"a.txt\"\r\n\
+            \r\n\
+            a\r\n\
+            --BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"file2\"; filename=\"b.txt\"\r\n\
+            \r\n\
+            b\r\n\
+            --BOUNDARY--\r\n";
+
+        assert!(parse_upload(body, "BOUNDARY", 1_048_576, 1).is_none());
+    }
+
+    #[test]
+    fn respond_head_omits_body_but_keeps_content_length() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            respond(&mut server, &HttpMethod::HEAD, b"hello", "text/plain", None).unwrap();
+        });
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let response = String::from_utf8_lossy(&buf[..n]);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("Content-Length: 7"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn respond_range_inverted_is_416() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            let content = b"0123456789";
+            respond_range(
+                &mut server,
+                &HttpMethod::GET,
+                content,
+                "text/plain",
+                None,
+                Some(ByteRange::Full(5, 2)),
+            )
+            .unwrap();
+        });
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let response = String::from_utf8_lossy(&buf[..n]);
+        assert!(response.starts_with("HTTP/1.1 416"), "{}", response);
+        assert!(
+            response.contains("Content-Range: bytes */10"),
+            "{}",
+            response
+        );
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn respond_range_zero_length_suffix_is_416() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            let content = b"0123456789";
+            respond_range(
+                &mut server,
+                &HttpMethod::GET,
+                content,
+                "text/plain",
+                None,
+                Some(ByteRange::Suffix(0)),
+            )
+            .unwrap();
+        });
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let response = String::from_utf8_lossy(&buf[..n]);
+        assert!(response.starts_with("HTTP/1.1 416"), "{}", response);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn respond_range_out_of_bounds_is_416() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            let content = b"0123456789";
+            respond_range(
+                &mut server,
+                &HttpMethod::GET,
+                content,
+                "text/plain",
+                None,
+                Some(ByteRange::From(100)),
+            )
+            .unwrap();
+        });
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let response = String::from_utf8_lossy(&buf[..n]);
+        assert!(response.starts_with("HTTP/1.1 416"), "{}", response);
+        assert!(
+            response.contains("Content-Range: bytes */10"),
+            "{}",
+            response
+        );
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn respond_range_satisfiable_is_206_with_sliced_body() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            let content = b"0123456789";
+            respond_range(
+                &mut server,
+                &HttpMethod::GET,
+                content,
+                "text/plain",
+                None,
+                Some(ByteRange::Full(2, 5)),
+            )
+            .unwrap();
+        });
+
+        let buf = read_all(&mut client);
+        let response = String::from_utf8_lossy(&buf);
+        assert!(response.starts_with("HTTP/1.1 206"), "{}", response);
+        assert!(
+            response.contains("Content-Range: bytes 2-5/10"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("2345"), "{}", response);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn respond_range_oversize_suffix_clamps_to_whole_content() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            let content = b"0123456789";
+            respond_range(
+                &mut server,
+                &HttpMethod::GET,
+                content,
+                "text/plain",
+                None,
+                Some(ByteRange::Suffix(999_999)),
+            )
+            .unwrap();
+        });
+
+        let buf = read_all(&mut client);
+        let response = String::from_utf8_lossy(&buf);
+        assert!(response.starts_with("HTTP/1.1 206"), "{}", response);
+        assert!(
+            response.contains("Content-Range: bytes 0-9/10"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("0123456789"), "{}", response);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn decode_chunked_decodes_multiple_chunks() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            client.write_all(b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n").unwrap();
+        });
+
+        let result = decode_chunked(&mut server, Vec::new(), 1_000_000);
+        assert!(matches!(&result, Some(ChunkedBody::Ok(body)) if body == b"hello world"));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn decode_chunked_drains_trailer_before_returning() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            // send the chunk data and terminating size line first, then the
+            // trailer section and final CRLF after a delay: if the trailer
+            // weren't drained, the stray bytes would desync the next request
+            client.write_all(b"5\r\nhello\r\n0\r\n").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            client.write_all(b"X-Trailer: value\r\n\r\n").unwrap();
+        });
+
+        let result = decode_chunked(&mut server, Vec::new(), 1_000_000);
+        assert!(matches!(&result, Some(ChunkedBody::Ok(body)) if body == b"hello"));
+        writer.join().unwrap();
+
+        // the peer has closed: nothing of the trailer may be left on the stream
+        let mut rest = [0u8; 64];
+        assert_eq!(server.read(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn decode_chunked_exceeding_max_content_is_too_large() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            // a single 10-byte chunk, over a 5-byte max_content
+            client.write_all(b"a\r\n0123456789\r\n0\r\n\r\n").unwrap();
+        });
+
+        let result = decode_chunked(&mut server, Vec::new(), 5);
+        assert!(matches!(result, Some(ChunkedBody::TooLarge)));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn content_length_and_chunked_together_is_rejected() {
+        let (mut server, _client) = pair();
+        let raw_header = "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(HttpRequest::from(raw_header, Vec::new(), &mut server, 1_048_576).is_none());
+    }
+
+    #[test]
+    fn cookies_parses_pairs_and_defaults_to_empty() {
+        let (mut server, _client) = pair();
+        let raw_header = "GET / HTTP/1.1\r\nCookie: a=1; b=2; bare\r\n\r\n";
+        let request = HttpRequest::from(raw_header, Vec::new(), &mut server, 1_048_576).unwrap();
+        let cookies = request.cookies();
+        assert_eq!(cookies.get("a"), Some(&"1"));
+        assert_eq!(cookies.get("b"), Some(&"2"));
+        assert_eq!(cookies.get("bare"), None);
+
+        let (mut server, _client) = pair();
+        let raw_header = "GET / HTTP/1.1\r\n\r\n";
+        let request = HttpRequest::from(raw_header, Vec::new(), &mut server, 1_048_576).unwrap();
+        assert!(request.cookies().is_empty());
+    }
+
+    #[test]
+    fn body_bytes_round_trips_non_utf8_data() {
+        let (mut server, _client) = pair();
+        let body = vec![0u8, 159, 146, 150, 255];
+        let raw_header = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", body.len());
+        let request = HttpRequest::from(&raw_header, body.clone(), &mut server, 1_048_576).unwrap();
+        assert_eq!(request.body_bytes(), body.as_slice());
+        assert_eq!(request.body_str(), None);
+    }
+
+    #[test]
+    fn decode_chunked_oversized_size_line_is_bounded() {
+        let (mut server, mut client) = pair();
+        let writer = thread::spawn(move || {
+            // never send a CRLF: the chunk-size line buffering must give up
+            // instead of growing without bound
+            let _ = client.write_all(&vec![b'a'; MAX_CHUNK_SIZE_LINE + 1]);
+        });
+
+        assert!(decode_chunked(&mut server, Vec::new(), 1_000_000).is_none());
+        writer.join().unwrap();
     }
-    Ok((
-        match String::from_utf8(header) {
-            Ok(header) => header,
-            Err(err) => return Fail::from(err),
-        },
-        rest,
-    ))
 }